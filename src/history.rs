@@ -1,53 +1,262 @@
-use std::cell::RefCell;
-use std::rc::{Rc, Weak};
+// Tree struct based on the slab/arena pattern: all entries live in a single
+// Vec owned by the tree, and parent/child/current links are indices into it
+// rather than Rc/Weak pointers. This avoids reference cycles and RefCell
+// borrow juggling, and keeps the whole tree trivially cloneable (and, with
+// the flat index representation below, trivially serializable).
 
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
 
-// Tree struct based on https://doc.rust-lang.org/stable/book/ch15-06-reference-cycles.html
+use serde::{Deserialize, Serialize};
+
+/// An index into a `HistoryTree`'s entry arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeId(usize);
+
+// Half-life of the recency component of the frecency score: a directory
+// visited this long ago counts for half as much as one visited just now.
+const FRECENCY_HALF_LIFE_SECS: f64 = 3.0 * 24.0 * 60.0 * 60.0; // 3 days
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistoryTreeEntry {
     name: String, //TODO: use Path / PathComponent instead? or None? to represent root (and what else?) correctly
-    parent: Weak<Self>, // option is not needed (I guess), we can just use a null weak to represent the root
-    last_visited_child: Option<Weak<Self>>,
-    children: RefCell<Vec<Rc<Self>>>,
+    parent: Option<NodeId>, // None represents the root
+    last_visited_child: Option<NodeId>,
+    children: Vec<NodeId>,
+    visit_count: u32,
+    last_visited: SystemTime,
 }
 
-struct HistoryTree {
-    root: Rc<HistoryTreeEntry>,
-    current_entry: RefCell<Rc<HistoryTreeEntry>>,
+impl HistoryTreeEntry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn visit_count(&self) -> u32 {
+        self.visit_count
+    }
+
+    pub fn last_visited(&self) -> SystemTime {
+        self.last_visited
+    }
+
+    /// Combines recency and frequency into a single ranking score: each
+    /// visit counts for one point, and all points are decayed together by
+    /// how long before `now` the most recent visit was.
+    pub fn frecency(&self, now: SystemTime) -> f64 {
+        let age_secs = now
+            .duration_since(self.last_visited)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or(0.0);
+        let decay = 0.5f64.powf(age_secs / FRECENCY_HALF_LIFE_SECS);
+        self.visit_count as f64 * decay
+    }
+}
+
+// Bumped whenever the on-disk layout of `SerializedHistoryTree` changes.
+// Loading a file with an unknown version is treated the same as a missing
+// file (start fresh) rather than a hard error.
+const HISTORY_FILE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SerializedHistoryTree {
+    version: u32,
+    entries: Vec<HistoryTreeEntry>,
+    root: NodeId,
+    current: NodeId,
+}
+
+#[derive(Clone)]
+pub struct HistoryTree {
+    entries: Vec<HistoryTreeEntry>,
+    root: NodeId,
+    current: NodeId,
 }
 
 impl HistoryTree {
+    pub fn new(root_name: &str) -> Self {
+        let root = HistoryTreeEntry {
+            name: root_name.to_string(),
+            parent: None,
+            last_visited_child: None,
+            children: vec![],
+            visit_count: 0,
+            last_visited: SystemTime::now(),
+        };
 
-    pub fn current_entry(&self) -> Rc<HistoryTreeEntry> {
-        self.current_entry.borrow().clone()
+        HistoryTree {
+            entries: vec![root],
+            root: NodeId(0),
+            current: NodeId(0),
+        }
     }
 
-    pub fn visit(&mut self, fname: &str) {
-        let matching_child = self.current_entry.borrow().children.borrow().iter()
-            .find(|child| child.name == fname).map(|c| c.clone());
+    pub fn entry(&self, id: NodeId) -> &HistoryTreeEntry {
+        &self.entries[id.0]
+    }
 
-        if let Some(child) = matching_child {
+    pub fn children_of(&self, id: NodeId) -> &[NodeId] {
+        &self.entries[id.0].children
+    }
 
-            let mut previous_entry = self.current_entry.replace(Rc::clone(&child));
-            Rc::get_mut(&mut previous_entry).unwrap().last_visited_child = Some(Rc::downgrade(&child));
+    pub fn current_entry(&self) -> &HistoryTreeEntry {
+        self.entry(self.current)
+    }
 
+    pub fn current_id(&self) -> NodeId {
+        self.current
+    }
+
+    pub fn root_id(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn visit(&mut self, fname: &str) {
+        let matching_child = self.children_of(self.current).iter()
+            .copied()
+            .find(|&child| self.entry(child).name == fname);
+
+        let child = if let Some(child) = matching_child {
+            child
         } else {
-            let child = HistoryTreeEntry {
+            let child_id = NodeId(self.entries.len());
+            self.entries.push(HistoryTreeEntry {
                 name: fname.to_string(),
-                parent: Rc::downgrade(&self.current_entry.borrow()),
-                children: RefCell::new(vec![]),
+                parent: Some(self.current),
                 last_visited_child: None,
-            };
-            let child = Rc::new(child);
-            self.current_entry.borrow_mut().children.borrow_mut().push(Rc::clone(&child));
-            self.current_entry = RefCell::new(child);
-        }
+                children: vec![],
+                visit_count: 0,
+                last_visited: SystemTime::now(),
+            });
+            self.entries[self.current.0].children.push(child_id);
+            child_id
+        };
+
+        self.entries[self.current.0].last_visited_child = Some(child);
+        self.entries[child.0].visit_count += 1;
+        self.entries[child.0].last_visited = SystemTime::now();
+        self.current = child;
+    }
+
+    /// Returns `(NodeId, frecency score)` pairs for every entry in the tree,
+    /// most-frecent first, for a "jump to frequent/recent directory" command.
+    /// The root is included too; it is never visited, so it scores 0.
+    pub fn ranked_by_frecency(&self) -> Vec<(NodeId, f64)> {
+        let now = SystemTime::now();
+        let mut ranked: Vec<(NodeId, f64)> = self.entries.iter()
+            .enumerate()
+            .map(|(i, entry)| (NodeId(i), entry.frecency(now)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        ranked
     }
 
     pub fn go_up(&mut self) {
-        let maybe_parent = self.current_entry.borrow().parent.upgrade();
-        if let Some(parent) = maybe_parent {
-            self.current_entry = RefCell::new(Rc::clone(&parent));
-        } // if the parent is None, we're at the root, so no need to do anything
+        if let Some(parent) = self.entry(self.current).parent {
+            self.current = parent;
+        } // if there's no parent, we're at the root, so no need to do anything
+    }
+
+    /// Descends into the current entry's `last_visited_child`, if it has
+    /// one. This is the inverse of `go_up`: it retraces the one step the
+    /// user last took down from here.
+    pub fn go_to_last_visited_child(&mut self) {
+        if let Some(child) = self.entry(self.current).last_visited_child {
+            self.current = child;
+        } // no last-visited child means this entry was never descended into
+    }
+
+    /// Repeatedly follows `last_visited_child` links until reaching the
+    /// deepest previously-visited leaf, retracing the full path the user
+    /// last took into this subtree.
+    pub fn go_to_last_visited_leaf(&mut self) {
+        while let Some(child) = self.entry(self.current).last_visited_child {
+            self.current = child;
+        }
+    }
+
+    /// Breadth-first search starting from `start`, returning the first node
+    /// (including `start` itself) whose name satisfies `pred`.
+    fn find_bfs_from<F: Fn(&str) -> bool>(&self, start: NodeId, pred: F) -> Option<NodeId> {
+        let mut queue: VecDeque<NodeId> = VecDeque::new();
+        queue.push_back(start);
+
+        while let Some(id) = queue.pop_front() {
+            let entry = self.entry(id);
+            if pred(&entry.name) {
+                return Some(id);
+            }
+            queue.extend(entry.children.iter().copied());
+        }
+
+        None
+    }
+
+    /// Breadth-first search from the current node for the first entry whose
+    /// name satisfies `pred`.
+    pub fn find_bfs<F: Fn(&str) -> bool>(&self, pred: F) -> Option<NodeId> {
+        self.find_bfs_from(self.current, pred)
+    }
+
+    /// Breadth-first search from the root for the first entry whose name
+    /// satisfies `pred`, regardless of where `current` is in the tree.
+    pub fn find_bfs_global<F: Fn(&str) -> bool>(&self, pred: F) -> Option<NodeId> {
+        self.find_bfs_from(self.root, pred)
+    }
+
+    /// Breadth-first search from the current node for the first entry whose
+    /// name contains `fragment`.
+    pub fn find_fuzzy(&self, fragment: &str) -> Option<NodeId> {
+        self.find_bfs(|name| name.contains(fragment))
+    }
+
+    /// Breadth-first search from the root for the first entry whose name
+    /// contains `fragment`.
+    pub fn find_fuzzy_global(&self, fragment: &str) -> Option<NodeId> {
+        self.find_bfs_global(|name| name.contains(fragment))
+    }
+
+    /// Serializes the tree to `path`, overwriting any existing file.
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        let serialized = SerializedHistoryTree {
+            version: HISTORY_FILE_VERSION,
+            entries: self.entries.clone(),
+            root: self.root,
+            current: self.current,
+        };
+        let json = serde_json::to_string(&serialized)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(path, json)
+    }
+
+    /// Loads a tree previously written by `save_to`. Returns `Ok(None)` if
+    /// `path` doesn't exist yet, or if the file was written by an
+    /// incompatible (newer or older) version, so that callers can fall back
+    /// to a fresh tree instead of crashing on a stale or foreign file.
+    pub fn load_from(path: &Path) -> io::Result<Option<Self>> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let serialized: SerializedHistoryTree = match serde_json::from_str(&json) {
+            Ok(serialized) => serialized,
+            Err(_) => return Ok(None),
+        };
+
+        if serialized.version != HISTORY_FILE_VERSION {
+            return Ok(None);
+        }
+
+        Ok(Some(HistoryTree {
+            entries: serialized.entries,
+            root: serialized.root,
+            current: serialized.current,
+        }))
     }
 
 }
@@ -57,17 +266,7 @@ mod tests_for_history_tree {
     use super::*;
 
     fn init_history_tree() -> HistoryTree {
-        let root = Rc::new(HistoryTreeEntry {
-            name: "/".to_string(),
-            parent: Weak::new(),
-            last_visited_child: None,
-            children: RefCell::new(vec![]),
-        });
-
-        HistoryTree {
-            root: Rc::clone(&root),
-            current_entry: RefCell::new(root),
-        }
+        HistoryTree::new("/")
     }
 
     #[test]
@@ -76,12 +275,15 @@ mod tests_for_history_tree {
 
         tree.visit("foo");
         assert_eq!(tree.current_entry().name, "foo");
-        assert_eq!(tree.current_entry().parent.upgrade().unwrap().name, "/");
+        let parent = tree.entry(tree.current_entry().parent.unwrap());
+        assert_eq!(parent.name, "/");
 
         tree.visit("bar");
         assert_eq!(tree.current_entry().name, "bar");
-        assert_eq!(tree.current_entry().parent.upgrade().unwrap().name, "foo");
-        assert_eq!(tree.current_entry().parent.upgrade().unwrap().parent.upgrade().unwrap().name, "/");
+        let parent_id = tree.current_entry().parent.unwrap();
+        assert_eq!(tree.entry(parent_id).name, "foo");
+        let grandparent_id = tree.entry(parent_id).parent.unwrap();
+        assert_eq!(tree.entry(grandparent_id).name, "/");
 
     }
 
@@ -94,41 +296,180 @@ mod tests_for_history_tree {
 
         tree.go_up();
         assert_eq!(tree.current_entry().name, "foo");
-        assert_eq!(tree.current_entry().children.borrow()[0].name, "bar");
+        assert_eq!(tree.entry(tree.children_of(tree.current_id())[0]).name, "bar");
 
         tree.go_up();
         assert_eq!(tree.current_entry().name, "/");
-        assert_eq!(tree.current_entry().children.borrow()[0].name, "foo");
+        assert_eq!(tree.entry(tree.children_of(tree.current_id())[0]).name, "foo");
 
         tree.go_up();
         assert_eq!(tree.current_entry().name, "/");
-        assert_eq!(tree.current_entry().children.borrow()[0].name, "foo");
+        assert_eq!(tree.entry(tree.children_of(tree.current_id())[0]).name, "foo");
+
+    }
+
+    #[test]
+    fn test_history_tree_revisit_updates_last_visited_child() {
+        let mut tree = init_history_tree();
+
+        tree.visit("foo");
+        tree.visit("bar");
+        tree.go_up();
+        tree.go_up();
+
+        tree.visit("foo");
+        assert_eq!(tree.current_entry().name, "foo");
+        assert_eq!(
+            tree.entry(tree.root_id()).last_visited_child,
+            Some(tree.current_id())
+        );
+        assert_eq!(
+            tree.entry(tree.current_id()).last_visited_child.map(|id| tree.entry(id).name.clone()),
+            Some("bar".to_string())
+        );
+    }
+
+    #[test]
+    fn test_history_tree_save_and_load_round_trip() {
+        let mut tree = init_history_tree();
+        tree.visit("foo");
+        tree.visit("bar");
+        tree.go_up();
+        tree.visit("baz");
+
+        let path = std::env::temp_dir().join("tere_test_history_round_trip.json");
+        tree.save_to(&path).unwrap();
+
+        let loaded = HistoryTree::load_from(&path).unwrap().unwrap();
+        assert_eq!(loaded.current_entry().name, "baz");
+        assert_eq!(loaded.entry(loaded.root_id()).name, "/");
+        assert_eq!(
+            loaded.entry(loaded.current_id()).parent.map(|id| loaded.entry(id).name.clone()),
+            Some("foo".to_string())
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_history_tree_load_from_missing_file_returns_none() {
+        let path = std::env::temp_dir().join("tere_test_history_does_not_exist.json");
+        let _ = std::fs::remove_file(&path);
+        assert!(HistoryTree::load_from(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_history_tree_visit_updates_frecency_metadata() {
+        let mut tree = init_history_tree();
+
+        tree.visit("foo");
+        assert_eq!(tree.current_entry().visit_count(), 1);
+
+        tree.go_up();
+        tree.visit("foo");
+        assert_eq!(tree.current_entry().visit_count(), 2);
+    }
+
+    #[test]
+    fn test_history_tree_ranked_by_frecency_favors_more_visited() {
+        let mut tree = init_history_tree();
+
+        tree.visit("rarely_visited");
+        tree.go_up();
+
+        tree.visit("often_visited");
+        tree.go_up();
+        tree.visit("often_visited");
+        tree.go_up();
+        tree.visit("often_visited");
+        tree.go_up();
+
+        let ranked = tree.ranked_by_frecency();
+        let top = ranked[0].0;
+        assert_eq!(tree.entry(top).name, "often_visited");
+    }
+
+    #[test]
+    fn test_history_tree_find_bfs_from_current() {
+        let mut tree = init_history_tree();
+
+        tree.visit("foo");
+        tree.visit("bar");
+        tree.go_up();
+        tree.visit("baz");
+        tree.go_up();
+        tree.go_up();
+
+        // current is back at root; "bar" is two levels down under "foo"
+        let found = tree.find_bfs(|name| name == "bar").unwrap();
+        assert_eq!(tree.entry(found).name, "bar");
 
+        tree.visit("foo");
+        // from "foo", "bar" and "baz" are both children, nothing named "qux" exists
+        assert!(tree.find_bfs(|name| name == "qux").is_none());
     }
 
     #[test]
-    fn test_tree_pointer_counts() {
+    fn test_history_tree_find_bfs_global_ignores_current_position() {
         let mut tree = init_history_tree();
+
         tree.visit("foo");
-        let foo = Rc::downgrade(&tree.current_entry());
         tree.visit("bar");
-        let bar = Rc::downgrade(&tree.current_entry());
+        tree.go_up();
+        tree.go_up();
+        tree.visit("other");
 
-        assert_eq!(Rc::weak_count(&tree.root), 1); // the child (foo)
+        // current is under "other", but "bar" lives under "foo"
+        assert!(tree.find_bfs(|name| name == "bar").is_none());
+        let found = tree.find_bfs_global(|name| name == "bar").unwrap();
+        assert_eq!(tree.entry(found).name, "bar");
+    }
 
-        assert_eq!(Weak::strong_count(&foo), 1); // the root
-        assert_eq!(Weak::weak_count(&foo), 2); // the child and the variable 'foo' above
+    #[test]
+    fn test_history_tree_find_fuzzy() {
+        let mut tree = init_history_tree();
 
-        assert_eq!(Weak::strong_count(&bar), 2); // the parent (foo) and the tree current entry
-        assert_eq!(Weak::weak_count(&bar), 1); // the variable 'bar' above
+        tree.visit("projects");
+        tree.visit("tere-rewrite");
 
-        tree.go_up(); tree.go_up();
-        assert_eq!(Weak::strong_count(&bar), 1); // the parent only now
-        assert_eq!(Weak::weak_count(&bar), 1); // the variable 'bar' above
+        let found = tree.find_fuzzy_global("rewrite").unwrap();
+        assert_eq!(tree.entry(found).name, "tere-rewrite");
+    }
 
+    #[test]
+    fn test_history_tree_go_to_last_visited_child() {
+        let mut tree = init_history_tree();
+
+        tree.visit("foo");
+        tree.visit("bar");
+        tree.go_up();
+        tree.go_up();
+
+        assert_eq!(tree.current_entry().name, "/");
+        tree.go_to_last_visited_child();
+        assert_eq!(tree.current_entry().name, "foo");
+        tree.go_to_last_visited_child();
+        assert_eq!(tree.current_entry().name, "bar");
+
+        // "bar" has no children, so this is a no-op
+        tree.go_to_last_visited_child();
+        assert_eq!(tree.current_entry().name, "bar");
+    }
+
+    #[test]
+    fn test_history_tree_go_to_last_visited_leaf() {
+        let mut tree = init_history_tree();
+
+        tree.visit("foo");
+        tree.visit("bar");
         tree.visit("baz");
-        assert_eq!(Rc::weak_count(&tree.root), 2); // two children
+        tree.go_up();
+        tree.go_up();
+        tree.go_up();
 
+        assert_eq!(tree.current_entry().name, "/");
+        tree.go_to_last_visited_leaf();
+        assert_eq!(tree.current_entry().name, "baz");
     }
 
 }